@@ -1,73 +1,315 @@
+use std::fmt;
+
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
 use crate::records::Record;
 
-/// Collapse paired-reads to individual fragments
-pub fn collapse_to_fragment(reads: &Vec<Record>) -> Vec<Record> {
+/// Character emitted for a position where the two mates disagree and neither base
+/// quality is higher than the other, so no confident call can be made.
+const AMBIGUOUS_CALL: char = '.';
+
+/// Character used to pad a gap between non-overlapping mates.
+const PAD_CALL: char = 'x';
+
+/// Lowest-Phred filler used for the quality string at padded gap positions, which have
+/// no real base backing them.
+const PAD_QUAL: char = '!';
+
+/// How the overlapping region of a pair of mates is reconciled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlapStrategy {
+    /// Pure coordinate arithmetic: assumes the call string length always equals
+    /// `end - start` and slices the overlap directly. Cheap, but rejects fragments
+    /// that carry an indel relative to the reference.
+    Arithmetic,
+    /// Diff the overlapping call strings with a patience diff to find the true
+    /// base-for-base correspondence before merging. Costs more per fragment, but
+    /// tolerates indels that the arithmetic path rejects.
+    Diff,
+}
+
+impl Default for OverlapStrategy {
+    fn default() -> Self {
+        OverlapStrategy::Arithmetic
+    }
+}
+
+/// A fragment whose computed coordinates don't match the length of its merged call
+/// string, returned instead of aborting so a caller can count and log it and move on.
+#[derive(Debug)]
+pub struct MalformedFragmentError {
+    pub read1: Record,
+    pub read2: Record,
+}
+
+impl fmt::Display for MalformedFragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Malformed collapsed fragment.")?;
+        writeln!(f, "Read 1: {}", self.read1)?;
+        write!(f, "Read 2: {}", self.read2)
+    }
+}
+
+impl std::error::Error for MalformedFragmentError {}
+
+/// Collapse paired-reads to individual fragments, using the fast arithmetic overlap
+/// strategy. See [`collapse_to_fragment_with_strategy`] to opt into indel tolerance.
+pub fn collapse_to_fragment(reads: &Vec<Record>) -> Result<Vec<Record>, MalformedFragmentError> {
+    collapse_to_fragment_with_strategy(reads, OverlapStrategy::default())
+}
+
+/// Collapse paired-reads to individual fragments using the given [`OverlapStrategy`].
+pub fn collapse_to_fragment_with_strategy(
+    reads: &Vec<Record>,
+    strategy: OverlapStrategy,
+) -> Result<Vec<Record>, MalformedFragmentError> {
     // Figure out which read in vector is read 1 and read 2
     let (idx1, idx2) = if reads[0].get_read_number() == &1 { (0, 1) } else { (1, 0) };
 
-    let mut r1: Record = reads[idx1].clone();
-    let     r2: Record = reads[idx2].clone();
+    let r1: Record = reads[idx1].clone();
+    let r2: Record = reads[idx2].clone();
 
     // If reads are on separate chromosomes, then return without processing
     if r1.get_chr() != r2.get_chr() {
-        return Vec::from([r1, r2]);
+        return Ok(Vec::from([r1, r2]));
     }
 
     // Figure out where reads are relative to one another for overlapping
     if r1.get_start() > r2.get_start() { // dovetail only
-        return vec!(collapse_dovetail(r1, r2));
+        Ok(vec!(collapse_dovetail(r1, r2, strategy)?))
     } else if r1.get_start() < r2.get_start() {
         if r1.get_end() >= r2.get_end() { // read 1 entirely overlaps read 2
-            r1.set_read_number(0);
-            return vec!(r1);
+            Ok(vec!(collapse_contained(r1, r2, strategy)?))
         } else { // canonical overlap
-            return vec!(collapse_canonical_proper_pair(r1, r2));
+            Ok(vec!(collapse_canonical_proper_pair(r1, r2, strategy)?))
         }
     } else {
         if r1.get_end() >= r2.get_end() { // read 1 entirely overlaps read 2
-            r1.set_read_number(0);
-            return vec!(r1);
+            Ok(vec!(collapse_contained(r1, r2, strategy)?))
         } else { // canonical overlap
-            return vec!(collapse_canonical_proper_pair(r1, r2));
+            Ok(vec!(collapse_canonical_proper_pair(r1, r2, strategy)?))
         }
     }
 }
 
+/// Reconcile a single pair of aligned call/quality characters covered by both mates.
+///
+/// Positions where one mate's call string is padded with `x` (no CpG/GpC context in
+/// that read) are skipped in favor of the other mate's call. Otherwise, agreeing calls
+/// keep the higher of the two qualities; disagreeing calls are resolved in favor of the
+/// higher-quality mate, or marked [`AMBIGUOUS_CALL`] on a quality tie.
+fn consensus_char(c1: char, q1: char, c2: char, q2: char) -> (char, char) {
+    if c1 == PAD_CALL {
+        return (c2, q2);
+    }
+    if c2 == PAD_CALL {
+        return (c1, q1);
+    }
+
+    if c1 == c2 {
+        (c1, if q1 >= q2 { q1 } else { q2 })
+    } else if q1 > q2 {
+        (c1, q1)
+    } else if q2 > q1 {
+        (c2, q2)
+    } else {
+        (AMBIGUOUS_CALL, q1)
+    }
+}
+
+/// Run [`consensus_char`] over two equal-length, aligned call/quality slices, returning
+/// the merged call and quality strings.
+fn consensus_merge(calls1: &str, qual1: &str, calls2: &str, qual2: &str) -> (String, String) {
+    let mut calls = String::with_capacity(calls1.len());
+    let mut qual = String::with_capacity(calls1.len());
+
+    for (((c1, q1), c2), q2) in calls1.chars().zip(qual1.chars()).zip(calls2.chars()).zip(qual2.chars()) {
+        let (call, q) = consensus_char(c1, q1, c2, q2);
+        calls.push(call);
+        qual.push(q);
+    }
+
+    (calls, qual)
+}
+
+/// Reconcile two overlapping call/quality regions with a Myers diff instead of assuming
+/// they line up column-for-column, so an indel in either mate doesn't abort the merge.
+/// The diff is computed once from the CpG call strings; the GpC call strings (when
+/// present) are merged by replaying that same `DiffOp` script rather than diffing
+/// independently, so the CpG and GpC merges can never disagree on how many reference
+/// columns the overlap spans (two independent diffs routinely produce different edit
+/// scripts for the same overlap, since the two alphabets differ).
+///
+/// Matched ("equal") ranges run through [`consensus_merge`]; a delete range (present
+/// only in read 1) keeps read 1's bases, and an insert range (present only in read 2)
+/// keeps read 2's bases.
+///
+/// Returns the merged CpG call string, the merged GpC call string (present iff both
+/// inputs were), the number of reference-consuming columns they represent (which may
+/// differ from either input's length when an indel is present), and the merged quality
+/// string.
+fn diff_merge(
+    cpg1: &str,
+    qual1: &str,
+    cpg2: &str,
+    qual2: &str,
+    gpc1: Option<&str>,
+    gpc2: Option<&str>,
+) -> (String, Option<String>, usize, String) {
+    let c1: Vec<char> = cpg1.chars().collect();
+    let c2: Vec<char> = cpg2.chars().collect();
+    let q1: Vec<char> = qual1.chars().collect();
+    let q2: Vec<char> = qual2.chars().collect();
+    let has_gpc = gpc1.is_some() && gpc2.is_some();
+    let g1: Vec<char> = gpc1.map(|s| s.chars().collect()).unwrap_or_default();
+    let g2: Vec<char> = gpc2.map(|s| s.chars().collect()).unwrap_or_default();
+
+    let ops = capture_diff_slices(Algorithm::Myers, &c1, &c2);
+
+    let range = |v: &[char], start: usize, len: usize| -> String { v[start..start + len].iter().collect() };
+
+    let mut cpg = String::new();
+    let mut gpc = String::new();
+    let mut qual = String::new();
+    let mut ref_columns = 0usize;
+
+    let mut emit_consensus = |old_index: usize, new_index: usize, len: usize, cpg: &mut String, gpc: &mut String, qual: &mut String| {
+        let (merged_cpg, merged_qual) = consensus_merge(&range(&c1, old_index, len), &range(&q1, old_index, len), &range(&c2, new_index, len), &range(&q2, new_index, len));
+        cpg.push_str(&merged_cpg);
+        qual.push_str(&merged_qual);
+        if has_gpc {
+            let (merged_gpc, _) = consensus_merge(&range(&g1, old_index, len), &range(&q1, old_index, len), &range(&g2, new_index, len), &range(&q2, new_index, len));
+            gpc.push_str(&merged_gpc);
+        }
+    };
+
+    for op in ops {
+        match op {
+            DiffOp::Equal { old_index, new_index, len } => {
+                emit_consensus(old_index, new_index, len, &mut cpg, &mut gpc, &mut qual);
+                ref_columns += len;
+            }
+            DiffOp::Delete { old_index, old_len, .. } => {
+                cpg.extend(&c1[old_index..old_index + old_len]);
+                qual.extend(&q1[old_index..old_index + old_len]);
+                if has_gpc {
+                    gpc.extend(&g1[old_index..old_index + old_len]);
+                }
+                ref_columns += old_len;
+            }
+            DiffOp::Insert { new_index, new_len, .. } => {
+                cpg.extend(&c2[new_index..new_index + new_len]);
+                qual.extend(&q2[new_index..new_index + new_len]);
+                if has_gpc {
+                    gpc.extend(&g2[new_index..new_index + new_len]);
+                }
+                ref_columns += new_len;
+            }
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                if old_len == new_len {
+                    // Same-length mismatched span: both mates cover the same
+                    // reference columns, they just disagree on the call, so run it
+                    // through consensus same as an `Equal` range rather than letting
+                    // read 1 win automatically.
+                    emit_consensus(old_index, new_index, old_len, &mut cpg, &mut gpc, &mut qual);
+                } else {
+                    // Lengths differ: there's no column-for-column correspondence to
+                    // reconcile, so fall back to read 1's bases for this span.
+                    cpg.extend(&c1[old_index..old_index + old_len]);
+                    qual.extend(&q1[old_index..old_index + old_len]);
+                    if has_gpc {
+                        gpc.extend(&g1[old_index..old_index + old_len]);
+                    }
+                }
+                ref_columns += old_len;
+            }
+        }
+    }
+
+    (cpg, has_gpc.then_some(gpc), ref_columns, qual)
+}
+
+/// Merge the overlapping CpG (and, if present, GpC) call/quality regions of two mates
+/// using `strategy`, returning the merged CpG calls, the merged GpC calls (present iff
+/// both inputs were), the number of reference-consuming columns the overlap covers, and
+/// the merged quality string.
+#[allow(clippy::too_many_arguments)]
+fn merge_overlap(
+    strategy: OverlapStrategy,
+    cpg1: &str,
+    qual1: &str,
+    cpg2: &str,
+    qual2: &str,
+    gpc1: Option<&str>,
+    gpc2: Option<&str>,
+) -> (String, Option<String>, usize, String) {
+    match strategy {
+        OverlapStrategy::Arithmetic => {
+            let (cpg, qual) = consensus_merge(cpg1, qual1, cpg2, qual2);
+            let columns = cpg.len();
+            let gpc = match (gpc1, gpc2) {
+                (Some(g1), Some(g2)) => Some(consensus_merge(g1, qual1, g2, qual2).0),
+                _ => None,
+            };
+            (cpg, gpc, columns, qual)
+        }
+        OverlapStrategy::Diff => diff_merge(cpg1, qual1, cpg2, qual2, gpc1, gpc2),
+    }
+}
+
 /// Collapse dovetail reads
-fn collapse_dovetail(r1: Record, r2: Record) -> Record {
+fn collapse_dovetail(r1: Record, r2: Record, strategy: OverlapStrategy) -> Result<Record, MalformedFragmentError> {
     let     new_start: u64 = *r2.get_start();
     let mut new_end: u64 = *r1.get_end();
     let mut new_cpg;
     let mut new_gpc: Option<String> = None;
+    let mut new_qual;
 
     if r2.get_end() > r1.get_start() {
         // Difference in start locations
         let diff: usize = (r1.get_start() - r2.get_start()).try_into().unwrap();
 
-        // Pull out substring of read 2 to tack on to read 1
-        let r2_cpg = r2.get_cpg()[..diff].to_string();
-        new_cpg    = format!("{}{}", r2_cpg, r1.get_cpg());
+        // Read 1 and read 2 only overlap up to whichever mate ends first; read 2 may
+        // dovetail without extending all the way to (or past) read 1's end.
+        let overlap_len: usize = if r2.get_end() >= r1.get_end() {
+            r1.get_cpg().len()
+        } else {
+            (*r2.get_end() - *r1.get_start()).try_into().unwrap()
+        };
 
-        // Set GpC string if it exists
-        if !r1.get_gpc().is_none() {
-            let r1_gpc = r1.get_gpc().as_ref().unwrap();
-            let r2_gpc = r2.get_gpc().as_ref().unwrap();
+        // The overlapping region is merged via consensus instead of letting read 1
+        // win outright; any remainder of read 1 past the overlap is kept as-is. CpG
+        // and GpC are merged together so they share one reference-column mapping.
+        let r1_overlap_cpg = &r1.get_cpg()[..overlap_len];
+        let r1_overlap_qual = &r1.get_qual()[..overlap_len];
+        let r2_mid_cpg = &r2.get_cpg()[diff..diff + overlap_len];
+        let r2_mid_qual = &r2.get_qual()[diff..diff + overlap_len];
+        let r1_overlap_gpc = r1.get_gpc().as_deref().map(|g| &g[..overlap_len]);
+        let r2_mid_gpc = r2.get_gpc().as_deref().map(|g| &g[diff..diff + overlap_len]);
 
-            let tmp = &r2_gpc[..diff].to_string();
-            new_gpc = Some(format!("{}{}", tmp, r1_gpc));
+        let (merged_cpg, merged_gpc, merged_cols, merged_qual) =
+            merge_overlap(strategy, r1_overlap_cpg, r1_overlap_qual, r2_mid_cpg, r2_mid_qual, r1_overlap_gpc, r2_mid_gpc);
+
+        new_cpg  = format!("{}{}{}", &r2.get_cpg()[..diff], merged_cpg, &r1.get_cpg()[overlap_len..]);
+        new_qual = format!("{}{}{}", &r2.get_qual()[..diff], merged_qual, &r1.get_qual()[overlap_len..]);
+        new_end  = new_start + diff as u64 + merged_cols as u64 + (r1.get_cpg().len() - overlap_len) as u64;
+
+        if let (Some(r1_gpc), Some(r2_gpc), Some(merged_gpc)) = (r1.get_gpc().as_ref(), r2.get_gpc().as_ref(), merged_gpc) {
+            new_gpc = Some(format!("{}{}{}", &r2_gpc[..diff], merged_gpc, &r1_gpc[overlap_len..]));
         }
 
         // Handle case where read 2 starts before read 1 and ends after it
         if r2.get_end() > r1.get_end() {
-            new_end = *r2.get_end();
-
-            let tmp_start: usize = diff + r1.get_cpg().len();
-            let r2_cpg           = r2.get_cpg()[tmp_start..].to_string();
-            new_cpg              = format!("{}{}", new_cpg, r2_cpg);
+            let tmp_start: usize = diff + overlap_len;
+            let r2_cpg  = r2.get_cpg()[tmp_start..].to_string();
+            let r2_qual = r2.get_qual()[tmp_start..].to_string();
+            new_end  += r2_cpg.len() as u64;
+            new_cpg  = format!("{}{}", new_cpg, r2_cpg);
+            new_qual = format!("{}{}", new_qual, r2_qual);
 
             if !r1.get_gpc().is_none() {
                 let r2_gpc = r2.get_gpc().as_ref().unwrap();
-                let tmp    = &r2_gpc[tmp_start..].to_string();
+                let tmp = &r2_gpc[tmp_start..].to_string();
                 new_gpc = Some(format!("{}{}", new_gpc.unwrap(), tmp));
             }
         }
@@ -76,9 +318,11 @@ fn collapse_dovetail(r1: Record, r2: Record) -> Record {
         let diff: usize = (r1.get_start() - r2.get_end()).try_into().unwrap();
 
         // Padding added between end of read 2 and read 1
-        let pad: String = std::iter::repeat("x").take(diff).collect();
+        let pad: String = std::iter::repeat(PAD_CALL).take(diff).collect();
+        let pad_qual: String = std::iter::repeat(PAD_QUAL).take(diff).collect();
 
-        new_cpg = format!("{}{}{}", r2.get_cpg(), pad, r1.get_cpg());
+        new_cpg  = format!("{}{}{}", r2.get_cpg(), pad, r1.get_cpg());
+        new_qual = format!("{}{}{}", r2.get_qual(), pad_qual, r1.get_qual());
 
         // Handle GpC if it exists
         if !r1.get_gpc().is_none() {
@@ -88,14 +332,16 @@ fn collapse_dovetail(r1: Record, r2: Record) -> Record {
         }
     }
 
-    if new_end - new_start != new_cpg.len() as u64 {
-        eprintln!("Malformed collapsed fragment.",);
-        eprintln!("Read 1: {}", r1);
-        eprintln!("Read 2: {}", r2);
-        quit::with_code(1);
+    match strategy {
+        OverlapStrategy::Arithmetic => {
+            if new_end - new_start != new_cpg.len() as u64 {
+                return Err(MalformedFragmentError { read1: r1, read2: r2 });
+            }
+        }
+        OverlapStrategy::Diff => new_end = new_start + new_cpg.len() as u64,
     }
 
-    Record::new(
+    Ok(Record::new(
         r1.get_chr().to_string(),
         new_start,
         new_end,
@@ -104,24 +350,28 @@ fn collapse_dovetail(r1: Record, r2: Record) -> Record {
         *r1.get_bs_strand(),
         new_cpg,
         new_gpc,
-    )
+        new_qual,
+    ))
 }
 
 /// Collapse canonically-paired reads
-fn collapse_canonical_proper_pair(r1: Record, r2: Record) -> Record {
+fn collapse_canonical_proper_pair(r1: Record, r2: Record, strategy: OverlapStrategy) -> Result<Record, MalformedFragmentError> {
     let     new_start: u64 = *r1.get_start();
-    let     new_end: u64 = *r2.get_end();
+    let mut new_end: u64 = *r2.get_end();
     let     new_cpg;
     let mut new_gpc: Option<String> = None;
+    let     new_qual;
 
     if r2.get_start() > r1.get_end() {
         // Difference between end of read 1 and start of read 2
         let diff: usize = (r2.get_start() - r1.get_end()).try_into().unwrap();
 
         // Padding added between read 1 and read 2
-        let pad: String = std::iter::repeat("x").take(diff).collect();
+        let pad: String = std::iter::repeat(PAD_CALL).take(diff).collect();
+        let pad_qual: String = std::iter::repeat(PAD_QUAL).take(diff).collect();
 
-        new_cpg = format!("{}{}{}", r1.get_cpg(), pad, r2.get_cpg());
+        new_cpg  = format!("{}{}{}", r1.get_cpg(), pad, r2.get_cpg());
+        new_qual = format!("{}{}{}", r1.get_qual(), pad_qual, r2.get_qual());
 
         // Handle GpC if it exists
         if !r1.get_gpc().is_none() {
@@ -131,28 +381,41 @@ fn collapse_canonical_proper_pair(r1: Record, r2: Record) -> Record {
         }
     } else {
         let diff: usize = (r1.get_end() - r2.get_start()).try_into().unwrap();
+        let r1_overlap_start: usize = r1.get_cpg().len() - diff;
 
-        let r2_cpg = r2.get_cpg()[diff..].to_string();
-        new_cpg    = format!("{}{}", r1.get_cpg(), r2_cpg);
+        // The last `diff` bases of read 1 and the first `diff` bases of read 2 cover
+        // the same reference positions; merge them via consensus rather than keeping
+        // read 1 unconditionally. CpG and GpC are merged together so they share one
+        // reference-column mapping.
+        let r1_tail_cpg = &r1.get_cpg()[r1_overlap_start..];
+        let r2_head_cpg = &r2.get_cpg()[..diff];
+        let r1_tail_qual = &r1.get_qual()[r1_overlap_start..];
+        let r2_head_qual = &r2.get_qual()[..diff];
+        let r1_tail_gpc = r1.get_gpc().as_deref().map(|g| &g[r1_overlap_start..]);
+        let r2_head_gpc = r2.get_gpc().as_deref().map(|g| &g[..diff]);
 
-        // Handle GpC if it exists
-        if !r1.get_gpc().is_none() {
-            let r1_gpc = r1.get_gpc().as_ref().unwrap();
-            let r2_gpc = r2.get_gpc().as_ref().unwrap();
+        let (merged_cpg, merged_gpc, merged_cols, merged_qual) =
+            merge_overlap(strategy, r1_tail_cpg, r1_tail_qual, r2_head_cpg, r2_head_qual, r1_tail_gpc, r2_head_gpc);
+
+        new_cpg  = format!("{}{}{}", &r1.get_cpg()[..r1_overlap_start], merged_cpg, &r2.get_cpg()[diff..]);
+        new_qual = format!("{}{}{}", &r1.get_qual()[..r1_overlap_start], merged_qual, &r2.get_qual()[diff..]);
+        new_end  = new_start + r1_overlap_start as u64 + merged_cols as u64 + (r2.get_cpg().len() - diff) as u64;
 
-            let tmp = &r2_gpc[diff..].to_string();
-            new_gpc = Some(format!("{}{}", r1_gpc, tmp));
+        if let (Some(r1_gpc), Some(r2_gpc), Some(merged_gpc)) = (r1.get_gpc().as_ref(), r2.get_gpc().as_ref(), merged_gpc) {
+            new_gpc = Some(format!("{}{}{}", &r1_gpc[..r1_overlap_start], merged_gpc, &r2_gpc[diff..]));
         }
     }
 
-    if new_end - new_start != new_cpg.len() as u64 {
-        eprintln!("Malformed collapsed fragment.",);
-        eprintln!("Read 1: {}", r1);
-        eprintln!("Read 2: {}", r2);
-        quit::with_code(1);
+    match strategy {
+        OverlapStrategy::Arithmetic => {
+            if new_end - new_start != new_cpg.len() as u64 {
+                return Err(MalformedFragmentError { read1: r1, read2: r2 });
+            }
+        }
+        OverlapStrategy::Diff => new_end = new_start + new_cpg.len() as u64,
     }
 
-    Record::new(
+    Ok(Record::new(
         r1.get_chr().to_string(),
         new_start,
         new_end,
@@ -161,5 +424,143 @@ fn collapse_canonical_proper_pair(r1: Record, r2: Record) -> Record {
         *r1.get_bs_strand(),
         new_cpg,
         new_gpc,
-    )
+        new_qual,
+    ))
+}
+
+/// Collapse a pair where read 2's span falls entirely within read 1's (including the
+/// case where the two share a start or end position). Read 2 is merged into the
+/// overlapping portion of read 1 via consensus rather than discarded outright; the
+/// remainder of read 1 on either side of read 2 is kept as-is.
+fn collapse_contained(r1: Record, r2: Record, strategy: OverlapStrategy) -> Result<Record, MalformedFragmentError> {
+    let     new_start: u64 = *r1.get_start();
+    let mut new_end: u64;
+    let     new_cpg;
+    let mut new_gpc: Option<String> = None;
+    let     new_qual;
+
+    let offset: usize = (*r2.get_start() - *r1.get_start()).try_into().unwrap();
+    let overlap_len: usize = r2.get_cpg().len();
+
+    // CpG and GpC are merged together so they share one reference-column mapping.
+    let r1_overlap_cpg = &r1.get_cpg()[offset..offset + overlap_len];
+    let r1_overlap_qual = &r1.get_qual()[offset..offset + overlap_len];
+    let r1_overlap_gpc = r1.get_gpc().as_deref().map(|g| &g[offset..offset + overlap_len]);
+
+    let (merged_cpg, merged_gpc, merged_cols, merged_qual) = merge_overlap(
+        strategy,
+        r1_overlap_cpg,
+        r1_overlap_qual,
+        r2.get_cpg(),
+        r2.get_qual(),
+        r1_overlap_gpc,
+        r2.get_gpc().as_deref(),
+    );
+
+    new_cpg  = format!("{}{}{}", &r1.get_cpg()[..offset], merged_cpg, &r1.get_cpg()[offset + overlap_len..]);
+    new_qual = format!("{}{}{}", &r1.get_qual()[..offset], merged_qual, &r1.get_qual()[offset + overlap_len..]);
+    new_end  = new_start + offset as u64 + merged_cols as u64 + (r1.get_cpg().len() - offset - overlap_len) as u64;
+
+    if let (Some(r1_gpc), Some(merged_gpc)) = (r1.get_gpc().as_ref(), merged_gpc) {
+        new_gpc = Some(format!("{}{}{}", &r1_gpc[..offset], merged_gpc, &r1_gpc[offset + overlap_len..]));
+    }
+
+    match strategy {
+        OverlapStrategy::Arithmetic => {
+            if new_end - new_start != new_cpg.len() as u64 {
+                return Err(MalformedFragmentError { read1: r1, read2: r2 });
+            }
+        }
+        OverlapStrategy::Diff => new_end = new_start + new_cpg.len() as u64,
+    }
+
+    Ok(Record::new(
+        r1.get_chr().to_string(),
+        new_start,
+        new_end,
+        r1.get_name().to_string(),
+        0,
+        *r1.get_bs_strand(),
+        new_cpg,
+        new_gpc,
+        new_qual,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consensus_keeps_agreeing_call_and_max_quality() {
+        assert_eq!(consensus_char('Z', '#', 'Z', '*'), ('Z', '*'));
+    }
+
+    #[test]
+    fn consensus_prefers_higher_quality_mate_on_disagreement() {
+        assert_eq!(consensus_char('Z', '#', 'z', '*'), ('z', '*'));
+    }
+
+    #[test]
+    fn consensus_marks_ambiguous_on_quality_tie() {
+        assert_eq!(consensus_char('Z', '*', 'z', '*'), (AMBIGUOUS_CALL, '*'));
+    }
+
+    #[test]
+    fn consensus_skips_padding_in_favor_of_the_other_mate() {
+        assert_eq!(consensus_char(PAD_CALL, '!', 'H', '*'), ('H', '*'));
+        assert_eq!(consensus_char('h', '*', PAD_CALL, '!'), ('h', '*'));
+    }
+
+    #[test]
+    fn diff_merge_reconciles_a_deletion_in_read_two() {
+        // Read 1 carries an extra base relative to read 2's overlap.
+        let (merged, _, ref_columns, _) = diff_merge("ZzH", "III", "ZH", "II", None, None);
+        assert_eq!(merged, "ZzH");
+        assert_eq!(ref_columns, 3);
+    }
+
+    #[test]
+    fn diff_merge_reconciles_an_insertion_in_read_two() {
+        // Read 2 carries an extra base relative to read 1's overlap.
+        let (merged, _, ref_columns, _) = diff_merge("ZH", "II", "ZzH", "III", None, None);
+        assert_eq!(merged, "ZzH");
+        assert_eq!(ref_columns, 3);
+    }
+
+    #[test]
+    fn diff_merge_runs_consensus_over_a_same_length_mismatch() {
+        // Same-length disagreement: should resolve via quality, not default to read 1.
+        let (merged, _, ref_columns, _) = diff_merge("z", "#", "Z", "*", None, None);
+        assert_eq!(merged, "Z");
+        assert_eq!(ref_columns, 1);
+    }
+
+    #[test]
+    fn diff_merge_keeps_gpc_on_the_same_reference_columns_as_cpg() {
+        // Same deletion as above, but with GpC call strings attached: the GpC merge
+        // must be derived from the same DiffOp script as CpG, not diffed on its own,
+        // so the two never disagree on how many reference columns the overlap spans.
+        let (cpg, gpc, ref_columns, _) = diff_merge("ZzH", "III", "ZH", "II", Some("xxh"), Some("xH"));
+        assert_eq!(cpg, "ZzH");
+        assert_eq!(ref_columns, 3);
+        assert_eq!(gpc.unwrap().len(), ref_columns);
+    }
+
+    #[test]
+    fn collapse_to_fragment_merges_a_read_two_entirely_contained_in_read_one() {
+        use crate::records::BsStrand;
+
+        // Read 2 disagrees with read 1 at one position but carries the higher-quality
+        // base there, so the merge must pick it up rather than dropping read 2's
+        // evidence outright.
+        let r1 = Record::new("chr1".to_string(), 100, 105, "pair".to_string(), 1, BsStrand::Plus, "ZzHHz".to_string(), None, "I!III".to_string());
+        let r2 = Record::new("chr1".to_string(), 101, 104, "pair".to_string(), 2, BsStrand::Plus, "zhH".to_string(), None, "I~I".to_string());
+
+        let merged = collapse_to_fragment_with_strategy(&vec![r1, r2], OverlapStrategy::Arithmetic).unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].get_cpg(), "ZzhHz");
+        assert_eq!(merged[0].get_qual(), "II~II");
+        assert_eq!(merged[0].get_read_number(), &0);
+    }
 }
@@ -0,0 +1,4 @@
+pub mod collapse;
+pub mod parallel;
+pub mod reader;
+pub mod records;
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::sync::{mpsc, Condvar, Mutex};
+use std::thread;
+
+use rayon::iter::{ParallelBridge, ParallelIterator};
+
+use crate::collapse::{self, MalformedFragmentError, OverlapStrategy};
+use crate::reader::{PairReader, ReaderError};
+use crate::records::Record;
+
+/// Bound on pending read-pairs buffered between the BAM-reading producer thread and
+/// the rayon worker pool, and on collapsed fragments buffered between the worker pool
+/// and the result-draining thread, so memory stays flat regardless of input size.
+const CHANNEL_CAPACITY: usize = 4096;
+
+/// Bound on how far a worker may finish ahead of `drain_in_order`'s next expected
+/// index before it must wait. Without this, a single pair that's slow to collapse
+/// (e.g. a large overlap under [`OverlapStrategy::Diff`]) lets every faster pair
+/// behind it pile up in `drain_in_order`'s reorder buffer with no limit, defeating
+/// the bounded-memory design the channels above are meant to provide.
+const REORDER_WINDOW: u64 = CHANNEL_CAPACITY as u64;
+
+/// Shared state letting workers block until `drain_in_order` has caught up, instead of
+/// racing arbitrarily far ahead of the index it's waiting on.
+type ReorderGate = (Mutex<u64>, Condvar);
+
+/// Block the calling worker while `index` is more than [`REORDER_WINDOW`] ahead of the
+/// next index `drain_in_order` is waiting to emit.
+fn wait_for_turn(gate: &ReorderGate, index: u64) {
+    let (next_index, ready) = gate;
+    let mut next_index = next_index.lock().unwrap();
+    while index >= *next_index + REORDER_WINDOW {
+        next_index = ready.wait(next_index).unwrap();
+    }
+}
+
+/// Counters returned after a parallel collapsing run.
+#[derive(Debug, Default)]
+pub struct CollapseStats {
+    pub pairs_processed: u64,
+    pub malformed: u64,
+}
+
+/// A pair of mates read off the BAM, tagged with its original input position.
+struct PendingPair {
+    index: u64,
+    read1: Record,
+    read2: Record,
+}
+
+/// The outcome of collapsing a single pair, still tagged with its original input
+/// position so results can be written back out in order.
+struct CollapsedPair {
+    index: u64,
+    result: Result<Vec<Record>, MalformedFragmentError>,
+}
+
+/// Stream read pairs out of a name-sorted BAM/CRAM and collapse them in parallel on
+/// the rayon global thread pool, calling `on_record` for each resulting `Record` in
+/// original input order.
+///
+/// The BAM is read sequentially on a producer thread and handed to workers through a
+/// bounded channel; collapsed fragments are handed to a dedicated draining thread
+/// through a second bounded channel, so neither a slow writer nor a slow reader lets
+/// unbounded input pile up in memory. Workers are also held to [`REORDER_WINDOW`] via
+/// a shared gate, so a pair that's slow to collapse can't let the draining thread's
+/// own reorder buffer grow without limit either. Malformed fragments are counted
+/// instead of aborting the run, with details logged to stderr as they are encountered.
+pub fn collapse_bam_parallel<F>(path: &str, strategy: OverlapStrategy, on_record: F) -> Result<CollapseStats, ReaderError>
+where
+    F: FnMut(Record) + Send,
+{
+    let (pair_tx, pair_rx) = mpsc::sync_channel::<PendingPair>(CHANNEL_CAPACITY);
+    let (result_tx, result_rx) = mpsc::sync_channel::<CollapsedPair>(CHANNEL_CAPACITY);
+    let reorder_gate: ReorderGate = (Mutex::new(0), Condvar::new());
+
+    thread::scope(|scope| {
+        let producer = scope.spawn(move || -> Result<(), ReaderError> {
+            let mut reader = PairReader::open(path)?;
+            for (index, pair) in reader.pairs().enumerate() {
+                let (read1, read2) = pair?;
+                if pair_tx.send(PendingPair { index: index as u64, read1, read2 }).is_err() {
+                    break; // worker side gone
+                }
+            }
+            Ok(())
+        });
+
+        // Drains `result_rx` concurrently with the `for_each_with` call below, instead
+        // of after it, so collapsed records are written out as they become available
+        // rather than all piling up in memory first.
+        let drain = scope.spawn(|| drain_in_order(result_rx, &reorder_gate, on_record));
+
+        pair_rx.into_iter().par_bridge().for_each_with(result_tx, |result_tx, pending| {
+            wait_for_turn(&reorder_gate, pending.index);
+            let result = collapse::collapse_to_fragment_with_strategy(&vec![pending.read1, pending.read2], strategy);
+            let _ = result_tx.send(CollapsedPair { index: pending.index, result });
+        });
+
+        producer.join().expect("BAM-reading thread panicked")?;
+        Ok(drain.join().expect("result-draining thread panicked"))
+    })
+}
+
+/// Reorder collapsed fragments back into original input order and emit them through
+/// `on_record`, counting and logging any malformed ones instead of aborting.
+///
+/// `gate` is advanced as fragments are emitted, so workers waiting in [`wait_for_turn`]
+/// don't race more than [`REORDER_WINDOW`] indices ahead of what's been emitted here —
+/// bounding this function's own reorder buffer instead of letting it grow without limit.
+fn drain_in_order<F>(result_rx: mpsc::Receiver<CollapsedPair>, gate: &ReorderGate, mut on_record: F) -> CollapseStats
+where
+    F: FnMut(Record),
+{
+    let mut stats = CollapseStats::default();
+    let mut out_of_order: BTreeMap<u64, Result<Vec<Record>, MalformedFragmentError>> = BTreeMap::new();
+    let mut next_index = 0u64;
+
+    for collapsed in result_rx {
+        out_of_order.insert(collapsed.index, collapsed.result);
+
+        let mut advanced = false;
+        while let Some(result) = out_of_order.remove(&next_index) {
+            stats.pairs_processed += 1;
+            match result {
+                Ok(records) => records.into_iter().for_each(&mut on_record),
+                Err(e) => {
+                    stats.malformed += 1;
+                    eprintln!("Skipping malformed fragment ({} so far): {}", stats.malformed, e);
+                }
+            }
+            next_index += 1;
+            advanced = true;
+        }
+
+        if advanced {
+            let (shared_next_index, ready) = gate;
+            *shared_next_index.lock().unwrap() = next_index;
+            ready.notify_all();
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::BsStrand;
+
+    fn fragment(name: &str) -> Record {
+        Record::new("chr1".to_string(), 0, 1, name.to_string(), 0, BsStrand::Plus, "Z".to_string(), None, "I".to_string())
+    }
+
+    #[test]
+    fn drain_in_order_emits_in_original_input_order_despite_arrival_order() {
+        let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        // Send out of order: 2 arrives before 0 and 1.
+        tx.send(CollapsedPair { index: 2, result: Ok(vec![fragment("c")]) }).unwrap();
+        tx.send(CollapsedPair { index: 0, result: Ok(vec![fragment("a")]) }).unwrap();
+        tx.send(CollapsedPair { index: 1, result: Ok(vec![fragment("b")]) }).unwrap();
+        drop(tx);
+
+        let gate: ReorderGate = (Mutex::new(0), Condvar::new());
+        let mut seen = Vec::new();
+        let stats = drain_in_order(rx, &gate, |r| seen.push(r.get_name().clone()));
+
+        assert_eq!(seen, vec!["a", "b", "c"]);
+        assert_eq!(stats.pairs_processed, 3);
+        assert_eq!(stats.malformed, 0);
+    }
+
+    #[test]
+    fn drain_in_order_counts_malformed_fragments_without_emitting_them() {
+        let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let err = MalformedFragmentError { read1: fragment("r1"), read2: fragment("r2") };
+        tx.send(CollapsedPair { index: 0, result: Err(err) }).unwrap();
+        tx.send(CollapsedPair { index: 1, result: Ok(vec![fragment("ok")]) }).unwrap();
+        drop(tx);
+
+        let gate: ReorderGate = (Mutex::new(0), Condvar::new());
+        let mut seen = Vec::new();
+        let stats = drain_in_order(rx, &gate, |r| seen.push(r.get_name().clone()));
+
+        assert_eq!(seen, vec!["ok"]);
+        assert_eq!(stats.pairs_processed, 2);
+        assert_eq!(stats.malformed, 1);
+    }
+
+    #[test]
+    fn wait_for_turn_returns_immediately_within_the_reorder_window() {
+        let gate: ReorderGate = (Mutex::new(0), Condvar::new());
+        // Well inside the window: must not block.
+        wait_for_turn(&gate, REORDER_WINDOW - 1);
+    }
+
+    #[test]
+    fn wait_for_turn_unblocks_once_the_gate_advances_far_enough() {
+        use std::sync::Arc;
+
+        let gate: Arc<ReorderGate> = Arc::new((Mutex::new(0), Condvar::new()));
+        let waiter_gate = Arc::clone(&gate);
+
+        let waiter = thread::spawn(move || wait_for_turn(&waiter_gate, REORDER_WINDOW));
+
+        // Give the waiting thread a chance to block before we advance the gate.
+        thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+
+        *gate.0.lock().unwrap() = 1;
+        gate.1.notify_all();
+
+        waiter.join().unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+use rust_htslib::bam::record::{Aux, Cigar};
+use rust_htslib::bam::{self, Read};
+
+use crate::records::{BsStrand, Record};
+
+/// Default `ML` probability threshold (on the packed 0-255 scale) above which a
+/// base-modification call is reported as methylated.
+pub const DEFAULT_ML_THRESHOLD: u8 = 204; // ~0.8 probability
+
+/// Errors that can occur while pulling `Record`s out of a BAM/CRAM.
+#[derive(Debug)]
+pub enum ReaderError {
+    Htslib(rust_htslib::errors::Error),
+    MissingStrandTag(String),
+    UnpairedRead(String),
+}
+
+impl std::fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReaderError::Htslib(e) => write!(f, "htslib error: {}", e),
+            ReaderError::MissingStrandTag(name) => {
+                write!(f, "read '{}' is missing an XG/ZS bisulfite-strand tag", name)
+            }
+            ReaderError::UnpairedRead(name) => write!(f, "read '{}' has no mate in the input", name),
+        }
+    }
+}
+
+impl std::error::Error for ReaderError {}
+
+/// Reads primary alignments out of a name-sorted (or query-grouped) BAM/CRAM and
+/// groups them into read pairs, building the `Record`s that `collapse_to_fragment`
+/// expects instead of requiring a pre-parsed `Vec<Record>`.
+pub struct PairReader {
+    reader: bam::Reader,
+    ml_threshold: u8,
+}
+
+impl PairReader {
+    /// Open a BAM/CRAM for reading.
+    pub fn open(path: &str) -> Result<Self, ReaderError> {
+        let reader = bam::Reader::from_path(path).map_err(ReaderError::Htslib)?;
+        Ok(PairReader { reader, ml_threshold: DEFAULT_ML_THRESHOLD })
+    }
+
+    /// Override the `ML` threshold used when calling methylation from `MM`/`ML` tags.
+    pub fn with_ml_threshold(mut self, threshold: u8) -> Self {
+        self.ml_threshold = threshold;
+        self
+    }
+
+    /// Iterate over read pairs as `(read 1, read 2)` `Record`s, skipping secondary and
+    /// supplementary alignments. The input must be name-sorted or query-grouped so that
+    /// the two mates appear consecutively.
+    pub fn pairs(&mut self) -> impl Iterator<Item = Result<(Record, Record), ReaderError>> + '_ {
+        let header = self.reader.header().clone();
+        let ml_threshold = self.ml_threshold;
+
+        let mut pending: Option<bam::Record> = None;
+
+        std::iter::from_fn(move || loop {
+            let mut rec = bam::Record::new();
+            match self.reader.read(&mut rec) {
+                None => return pending.take().map(|r| Err(ReaderError::UnpairedRead(read_name(&r)))),
+                Some(Err(e)) => return Some(Err(ReaderError::Htslib(e))),
+                Some(Ok(())) => {}
+            }
+
+            if rec.is_secondary() || rec.is_supplementary() || rec.is_unmapped() {
+                continue;
+            }
+
+            match pending.take() {
+                None => pending = Some(rec),
+                Some(first) => {
+                    if read_name(&first) != read_name(&rec) {
+                        // `first` never got a mate before a new query name showed up;
+                        // surface that instead of silently dropping it. Start over
+                        // from this read, which may yet pair with what follows.
+                        let orphan = ReaderError::UnpairedRead(read_name(&first));
+                        pending = Some(rec);
+                        return Some(Err(orphan));
+                    }
+
+                    let (r1, r2) = if first.is_first_in_template() { (first, rec) } else { (rec, first) };
+
+                    let r1 = record_from_alignment(&r1, &header, ml_threshold, 1);
+                    let r2 = record_from_alignment(&r2, &header, ml_threshold, 2);
+                    return Some(r1.and_then(|r1| r2.map(|r2| (r1, r2))));
+                }
+            }
+        })
+    }
+}
+
+fn read_name(rec: &bam::Record) -> String {
+    String::from_utf8_lossy(rec.qname()).into_owned()
+}
+
+/// Build a `Record` from a single aligned mate.
+fn record_from_alignment(
+    rec: &bam::Record,
+    header: &bam::HeaderView,
+    ml_threshold: u8,
+    read_number: u8,
+) -> Result<Record, ReaderError> {
+    let name = read_name(rec);
+
+    let chr = String::from_utf8_lossy(header.tid2name(rec.tid() as u32)).into_owned();
+    let start = rec.pos() as u64;
+    let end = start + reference_span(rec);
+
+    let bs_strand = bs_strand_from_tags(rec).ok_or_else(|| ReaderError::MissingStrandTag(name.clone()))?;
+
+    let (cpg, gpc) = calls_from_tags(rec, ml_threshold);
+    let qual = qual_string(rec);
+
+    Ok(Record::new(chr, start, end, name, read_number, bs_strand, cpg, gpc, qual))
+}
+
+/// Lowest-Phred filler used at reference columns a deletion/ref-skip covers, which have
+/// no read base backing them.
+const PAD_QUAL: char = '!';
+
+/// Per-base Phred quality string (ASCII, offset 33), mapped from read coordinates onto
+/// reference coordinates the same way [`calls_from_tags`] maps the CpG/GpC call
+/// strings, so all three stay the same length and positionally aligned.
+fn qual_string(rec: &bam::Record) -> String {
+    let read_qual: Vec<char> = rec.qual().iter().map(|q| (q + 33) as char).collect();
+    map_read_to_reference(rec, &read_qual, PAD_QUAL)
+}
+
+/// Number of reference bases consumed by the alignment's CIGAR.
+fn reference_span(rec: &bam::Record) -> u64 {
+    rec.cigar()
+        .iter()
+        .map(|op| match op {
+            Cigar::Match(n) | Cigar::Del(n) | Cigar::RefSkip(n) | Cigar::Equal(n) | Cigar::Diff(n) => *n as u64,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Determine the bisulfite strand from Bismark's `XG` tag (`CT`/`GA`) or a plain `ZS` tag.
+fn bs_strand_from_tags(rec: &bam::Record) -> Option<BsStrand> {
+    if let Ok(Aux::String(xg)) = rec.aux(b"XG") {
+        return match xg {
+            "CT" => Some(BsStrand::Plus),
+            "GA" => Some(BsStrand::Minus),
+            _ => None,
+        };
+    }
+
+    if let Ok(Aux::String(zs)) = rec.aux(b"ZS") {
+        return match zs.get(..2) {
+            Some("++") | Some("CT") => Some(BsStrand::Plus),
+            Some("--") | Some("GA") => Some(BsStrand::Minus),
+            _ => None,
+        };
+    }
+
+    None
+}
+
+/// Build the per-base CpG/GpC call strings, preferring Bismark's `XM` tag and falling
+/// back to the modern SAM `MM`/`ML` base-modification tags, then mapping the result
+/// from read coordinates onto reference coordinates via the CIGAR so the strings line
+/// up with `start`/`end` the same way `reference_span` does.
+fn calls_from_tags(rec: &bam::Record, ml_threshold: u8) -> (String, Option<String>) {
+    let (cpg_read, gpc_read) = if let Ok(Aux::String(xm)) = rec.aux(b"XM") {
+        let (cpg, gpc) = calls_from_xm(xm);
+        (cpg, Some(gpc))
+    } else if let (Ok(mm), Ok(ml)) = (rec.aux(b"MM"), rec.aux(b"ML")) {
+        let (cpg, gpc) = calls_from_mm_ml(rec, mm, ml, ml_threshold);
+        (cpg, Some(gpc))
+    } else {
+        (vec!['x'; rec.seq_len()], None)
+    };
+
+    let cpg = map_read_to_reference(rec, &cpg_read, 'x');
+    let gpc = gpc_read.map(|gpc_read| map_read_to_reference(rec, &gpc_read, 'x'));
+    (cpg, gpc)
+}
+
+/// Place a vector of per-read-base characters (one per base of `rec`'s `SEQ`, in read
+/// order) onto reference-relative columns by walking `rec`'s CIGAR: bases consumed by
+/// soft clips or insertions are dropped, and reference-only spans (deletions, ref
+/// skips) are filled with `pad`. The result has length `reference_span(rec)`, matching
+/// `end - start` from `record_from_alignment`.
+fn map_read_to_reference(rec: &bam::Record, values: &[char], pad: char) -> String {
+    let mut out = vec![pad; reference_span(rec) as usize];
+    let mut read_idx = 0usize;
+    let mut ref_idx = 0usize;
+
+    for op in rec.cigar().iter() {
+        match op {
+            Cigar::Match(n) | Cigar::Equal(n) | Cigar::Diff(n) => {
+                let n = *n as usize;
+                out[ref_idx..ref_idx + n].copy_from_slice(&values[read_idx..read_idx + n]);
+                read_idx += n;
+                ref_idx += n;
+            }
+            Cigar::Ins(n) | Cigar::SoftClip(n) => read_idx += *n as usize,
+            Cigar::Del(n) | Cigar::RefSkip(n) => ref_idx += *n as usize,
+            Cigar::HardClip(_) | Cigar::Pad(_) => {}
+        }
+    }
+
+    out.into_iter().collect()
+}
+
+/// Translate Bismark's combined `XM` methylation-call string into parallel per-read-base
+/// CpG and GpC call vectors (one char per base of the read, in read order, not yet
+/// mapped to reference coordinates), using `x` as a filler for positions that do not
+/// apply to that context.
+fn calls_from_xm(xm: &str) -> (Vec<char>, Vec<char>) {
+    let mut cpg = Vec::with_capacity(xm.len());
+    let mut gpc = Vec::with_capacity(xm.len());
+
+    for c in xm.chars() {
+        match c {
+            'Z' | 'z' => {
+                cpg.push(c);
+                gpc.push('x');
+            }
+            'H' | 'h' => {
+                cpg.push('x');
+                gpc.push(c);
+            }
+            _ => {
+                cpg.push('x');
+                gpc.push('x');
+            }
+        }
+    }
+
+    (cpg, gpc)
+}
+
+/// Translate the SAM `MM`/`ML` base-modification tags into per-read-base CpG/GpC call
+/// vectors (one char per base of the read, in read order, not yet mapped to reference
+/// coordinates).
+///
+/// `MM` gives, per modified base type, the count of unmodified bases of that type to
+/// skip before each modified occurrence; `ML` gives the matching modification
+/// probabilities on a 0-255 scale. Context (CpG vs. GpC) is taken from the read's own
+/// sequence around each modified cytosine.
+fn calls_from_mm_ml(rec: &bam::Record, mm: Aux, ml: Aux, ml_threshold: u8) -> (Vec<char>, Vec<char>) {
+    let mm = match mm {
+        Aux::String(s) => s,
+        _ => return (vec!['x'; rec.seq_len()], vec!['x'; rec.seq_len()]),
+    };
+    let probs: Vec<u8> = match ml {
+        Aux::ArrayU8(arr) => arr.iter().collect(),
+        _ => Vec::new(),
+    };
+
+    let seq = rec.seq().as_bytes();
+    let mut cpg = vec!['x'; rec.seq_len()];
+    let mut gpc = vec!['x'; rec.seq_len()];
+
+    // `MM` looks like "C+m?,<skip>,<skip>,...;" — we only care about cytosine modifications.
+    let mut prob_idx = 0;
+    for spec in mm.split(';').filter(|s| !s.is_empty()) {
+        let mut parts = spec.split(',');
+        let header = match parts.next() {
+            Some(h) => h,
+            None => continue,
+        };
+        if !header.starts_with('C') {
+            continue;
+        }
+
+        let mut c_seen = 0usize;
+        let mut next_pos = 0usize;
+        for skip in parts {
+            let skip: usize = match skip.parse() {
+                Ok(n) => n,
+                Err(_) => continue,
+            };
+
+            // Walk forward, counting unmodified C's, until `skip` of them have passed.
+            let mut remaining = skip;
+            while next_pos < seq.len() {
+                if seq[next_pos] == b'C' {
+                    if remaining == 0 {
+                        break;
+                    }
+                    remaining -= 1;
+                    c_seen += 1;
+                }
+                next_pos += 1;
+            }
+            if next_pos >= seq.len() {
+                break;
+            }
+
+            let prob = probs.get(prob_idx).copied().unwrap_or(0);
+            prob_idx += 1;
+
+            let is_cpg = next_pos + 1 < seq.len() && seq[next_pos + 1] == b'G';
+            let is_gpc = next_pos > 0 && seq[next_pos - 1] == b'G';
+            let call = if prob >= ml_threshold { 'Z' } else { 'z' };
+
+            if is_cpg {
+                cpg[next_pos] = call;
+            } else if is_gpc {
+                gpc[next_pos] = if prob >= ml_threshold { 'H' } else { 'h' };
+            }
+
+            next_pos += 1;
+            let _ = c_seen;
+        }
+    }
+
+    (cpg, gpc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::CigarString;
+
+    fn char_string(calls: &[char]) -> String {
+        calls.iter().collect()
+    }
+
+    #[test]
+    fn xm_splits_cpg_and_gpc_contexts() {
+        let (cpg, gpc) = calls_from_xm("Zzh.Hx");
+        assert_eq!(char_string(&cpg), "Zzxxxx");
+        assert_eq!(char_string(&gpc), "xxhxHx");
+    }
+
+    #[test]
+    fn xm_other_contexts_become_padding_in_both_strings() {
+        let (cpg, gpc) = calls_from_xm("xXUu");
+        assert_eq!(char_string(&cpg), "xxxx");
+        assert_eq!(char_string(&gpc), "xxxx");
+    }
+
+    #[test]
+    fn map_read_to_reference_drops_clipped_and_inserted_bases_and_pads_deletions() {
+        // 2 soft-clipped, 3 matched, 2 deleted (reference-only), 2 matched, 1 inserted
+        // (read-only), 2 matched: read-consuming ops total 10 bases, ref-consuming ops
+        // total 9 columns.
+        let mut rec = bam::Record::new();
+        let cigar = CigarString::try_from("2S3M2D2M1I2M").unwrap();
+        let seq = vec![b'A'; 10];
+        let qual = vec![30u8; 10];
+        rec.set(b"read1", Some(&cigar), &seq, &qual);
+
+        let calls: Vec<char> = "abcdefghij".chars().collect();
+        let mapped = map_read_to_reference(&rec, &calls, 'x');
+
+        assert_eq!(mapped, "cdexxfgij");
+        assert_eq!(mapped.len(), reference_span(&rec) as usize);
+    }
+
+    #[test]
+    fn qual_string_maps_onto_the_same_reference_columns_as_the_call_strings() {
+        // Same CIGAR as above: qual_string must come out the same length as the
+        // cpg/gpc strings built by map_read_to_reference, with deleted columns padded
+        // with PAD_QUAL rather than silently left at read length.
+        let mut rec = bam::Record::new();
+        let cigar = CigarString::try_from("2S3M2D2M1I2M").unwrap();
+        let seq = vec![b'A'; 10];
+        let qual: Vec<u8> = (0..10).collect();
+        rec.set(b"read1", Some(&cigar), &seq, &qual);
+
+        let mapped = qual_string(&rec);
+
+        assert_eq!(mapped.len(), reference_span(&rec) as usize);
+        assert_eq!(mapped.chars().filter(|&c| c == PAD_QUAL).count(), 2);
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+/// Strand the bisulfite conversion was performed on, as reported by the aligner
+/// (Bismark's `XG`/`ZS` tag or the equivalent bisulfite-strand tag).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BsStrand {
+    /// Original top strand (`OT`/`CT`)
+    Plus,
+    /// Original bottom strand (`OB`/`GA`)
+    Minus,
+}
+
+impl fmt::Display for BsStrand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BsStrand::Plus => write!(f, "+"),
+            BsStrand::Minus => write!(f, "-"),
+        }
+    }
+}
+
+/// A single read, read pair, or collapsed fragment.
+///
+/// `read_number` is `1`/`2` for an unmerged mate and `0` once the pair has been
+/// collapsed into a single fragment by [`crate::collapse::collapse_to_fragment`].
+#[derive(Debug, Clone)]
+pub struct Record {
+    chr: String,
+    start: u64,
+    end: u64,
+    name: String,
+    read_number: u8,
+    bs_strand: BsStrand,
+    cpg: String,
+    gpc: Option<String>,
+    qual: String,
+}
+
+impl Record {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        chr: String,
+        start: u64,
+        end: u64,
+        name: String,
+        read_number: u8,
+        bs_strand: BsStrand,
+        cpg: String,
+        gpc: Option<String>,
+        qual: String,
+    ) -> Self {
+        Record { chr, start, end, name, read_number, bs_strand, cpg, gpc, qual }
+    }
+
+    pub fn get_chr(&self) -> &String {
+        &self.chr
+    }
+
+    pub fn get_start(&self) -> &u64 {
+        &self.start
+    }
+
+    pub fn get_end(&self) -> &u64 {
+        &self.end
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_read_number(&self) -> &u8 {
+        &self.read_number
+    }
+
+    pub fn set_read_number(&mut self, read_number: u8) {
+        self.read_number = read_number;
+    }
+
+    pub fn get_bs_strand(&self) -> &BsStrand {
+        &self.bs_strand
+    }
+
+    pub fn get_cpg(&self) -> &String {
+        &self.cpg
+    }
+
+    pub fn get_gpc(&self) -> &Option<String> {
+        &self.gpc
+    }
+
+    /// Per-base Phred quality string, aligned one-to-one with [`Record::get_cpg`].
+    pub fn get_qual(&self) -> &String {
+        &self.qual
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.chr,
+            self.start,
+            self.end,
+            self.name,
+            self.read_number,
+            self.bs_strand,
+            self.cpg,
+            self.gpc.as_deref().unwrap_or("."),
+            self.qual,
+        )
+    }
+}